@@ -0,0 +1,4 @@
+//! maniparse: parsing and inspection of build manifests.
+pub mod manifest;
+
+pub use manifest::{Manifest, ManifestError, Target};
@@ -1,29 +1,247 @@
-use maniparse::Manifest;
-use std::env;
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
-        println!("Usage: maniparse <path>");
-        std::process::exit(1);
-    }
-    let results = Manifest::from_path(args[1].as_str())?;
-
-    println!("Name: {}", results.name());
-    println!("Version: {}", results.version());
-    println!("Exports: {:?}", results.export_keys());
-    println!("Flavors:");
-
-    let flavs = results.flavors()?;
-    flavs.iter().for_each(|v| println!("\t{}", v));
-    if let Some(ref mut keys) = results.export_keys() {
-        println!("Exports:");
-        for key in keys {
-            println!("\t{}", key);
-            results.exports_for(key).unwrap().iter().for_each(|t| println!("\t\t{}",t));
+use clap::{Parser, Subcommand, ValueEnum};
+use maniparse::{Manifest, ManifestError};
+use std::fmt;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+/// Inspect and validate manifest files.
+#[derive(Parser)]
+#[command(name = "maniparse", version, about = "Inspect and validate build manifests")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Parse a manifest and report any errors
+    Validate {
+        path: PathBuf,
+        #[arg(long, value_enum, default_value_t = Format::Text)]
+        format: Format,
+    },
+    /// List expanded flavours, including matrix expansion
+    Flavors {
+        path: PathBuf,
+        #[arg(long, value_enum, default_value_t = Format::Text)]
+        format: Format,
+    },
+    /// Dump export keys, or a single key's entries
+    Exports {
+        path: PathBuf,
+        #[arg(long)]
+        key: Option<String>,
+        #[arg(long, value_enum, default_value_t = Format::Text)]
+        format: Format,
+    },
+    /// Print the chosen requirement map
+    Requires {
+        path: PathBuf,
+        #[arg(long, value_enum)]
+        kind: Option<RequirementKind>,
+        #[arg(long, value_enum, default_value_t = Format::Text)]
+        format: Format,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum RequirementKind {
+    Load,
+    Build,
+    Test,
+    System,
+}
+
+#[derive(Clone, Copy, Default, ValueEnum)]
+enum Format {
+    #[default]
+    Text,
+    Json,
+    Yaml,
+}
+
+/// The value `validate --format json|yaml` prints on success.
+#[derive(serde::Serialize)]
+struct ValidationStatus {
+    status: &'static str,
+}
+
+/// Exit code used when a manifest fails to parse or validate.
+const EXIT_MANIFEST_FAILURE: u8 = 1;
+/// Exit code used when the command was invoked correctly but its arguments
+/// don't resolve against the manifest, e.g. an `--key` the manifest doesn't export.
+const EXIT_USAGE_ERROR: u8 = 2;
+
+/// Everything `run()` can fail with, distinguishing errors about the
+/// manifest itself from errors about how the CLI was asked to inspect it.
+#[derive(Debug)]
+enum AppError {
+    Usage(String),
+    Manifest(ManifestError),
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    Yaml(serde_yaml::Error),
+}
+
+impl AppError {
+    fn exit_code(&self) -> u8 {
+        match self {
+            AppError::Usage(_) => EXIT_USAGE_ERROR,
+            AppError::Manifest(_) | AppError::Io(_) | AppError::Json(_) | AppError::Yaml(_) => EXIT_MANIFEST_FAILURE,
         }
     }
- 
+}
 
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AppError::Usage(message) => write!(f, "{}", message),
+            AppError::Manifest(e) => write!(f, "{}", e),
+            AppError::Io(e) => write!(f, "{}", e),
+            AppError::Json(e) => write!(f, "{}", e),
+            AppError::Yaml(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for AppError {}
+
+impl From<ManifestError> for AppError {
+    fn from(e: ManifestError) -> Self {
+        AppError::Manifest(e)
+    }
+}
+
+impl From<std::io::Error> for AppError {
+    fn from(e: std::io::Error) -> Self {
+        AppError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for AppError {
+    fn from(e: serde_json::Error) -> Self {
+        AppError::Json(e)
+    }
+}
+
+impl From<serde_yaml::Error> for AppError {
+    fn from(e: serde_yaml::Error) -> Self {
+        AppError::Yaml(e)
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match run(cli.command) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::from(e.exit_code())
+        }
+    }
+}
+
+fn run(command: Command) -> Result<(), AppError> {
+    match command {
+        Command::Validate { path, format } => {
+            Manifest::from_path(path)?;
+            match format {
+                Format::Text => println!("ok"),
+                Format::Json => println!("{}", serde_json::to_string_pretty(&ValidationStatus { status: "ok" })?),
+                Format::Yaml => println!("{}", serde_yaml::to_string(&ValidationStatus { status: "ok" })?),
+            }
+            Ok(())
+        }
+        Command::Flavors { path, format } => {
+            let manifest = Manifest::from_path(path)?;
+            print_value(&manifest.flavors()?, format)
+        }
+        Command::Exports { path, key, format } => {
+            let manifest = Manifest::from_path(path)?;
+            match key {
+                Some(key) => {
+                    let entries = manifest
+                        .exports_for(&key)
+                        .ok_or_else(|| AppError::Usage(format!("no such export key '{}'", key)))?;
+                    print_value(&entries, format)
+                }
+                None => {
+                    let keys = manifest
+                        .export_keys()
+                        .map(|keys| keys.map(|k| k.as_str()).collect::<Vec<_>>())
+                        .unwrap_or_default();
+                    print_value(&keys, format)
+                }
+            }
+        }
+        Command::Requires { path, kind, format } => {
+            let manifest = Manifest::from_path(path)?;
+            let requirements = match kind {
+                Some(RequirementKind::Load) => manifest.load_requires(),
+                Some(RequirementKind::Build) => manifest.build_requires(),
+                Some(RequirementKind::Test) => manifest.test_requires(),
+                Some(RequirementKind::System) => manifest.system_requires(),
+                None => manifest.requires(),
+            };
+            print_value(&requirements.cloned().unwrap_or_default(), format)
+        }
+    }
+}
+
+fn print_value<T>(value: &T, format: Format) -> Result<(), AppError>
+where
+    T: serde::Serialize + std::fmt::Debug,
+{
+    match format {
+        Format::Text => println!("{:#?}", value),
+        Format::Json => println!("{}", serde_json::to_string_pretty(value)?),
+        Format::Yaml => println!("{}", serde_yaml::to_string(value)?),
+    }
     Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
+    fn write_temp_manifest(name: &str, contents: &str) -> PathBuf {
+        let mut path = std::env::temp_dir();
+        path.push(format!("maniparse-test-{}-{}.yaml", std::process::id(), name));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn validate_reports_ok_as_text_and_json() {
+        let path = write_temp_manifest("validate-ok", "name: app\nversion: \"1.0.0\"\n");
+        assert!(run(Command::Validate { path: path.clone(), format: Format::Text }).is_ok());
+        assert!(run(Command::Validate { path: path.clone(), format: Format::Json }).is_ok());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn validate_missing_manifest_is_a_manifest_failure() {
+        let err = run(Command::Validate {
+            path: PathBuf::from("/nonexistent/path/to/manifest.yaml"),
+            format: Format::Text,
+        })
+        .unwrap_err();
+        assert_eq!(err.exit_code(), EXIT_MANIFEST_FAILURE);
+    }
+
+    #[test]
+    fn exports_missing_key_is_a_usage_error() {
+        let path = write_temp_manifest(
+            "exports-missing-key",
+            "name: app\nversion: \"1.0.0\"\nexports:\n  tools:\n    - mytool\n",
+        );
+        let err = run(Command::Exports {
+            path: path.clone(),
+            key: Some("missing".to_string()),
+            format: Format::Text,
+        })
+        .unwrap_err();
+        assert_eq!(err.exit_code(), EXIT_USAGE_ERROR);
+        std::fs::remove_file(&path).unwrap();
+    }
 }
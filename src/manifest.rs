@@ -1,8 +1,8 @@
 //! Manifest parsing structures
 use anyhow::anyhow;
 use anyhow::Error as AnyError;
-use itertools::iproduct;
-use serde::Deserialize;
+use semver;
+use serde::{Deserialize, Serialize};
 use serde_yaml;
 use std::collections::HashMap;
 use std::fmt;
@@ -12,7 +12,70 @@ use mustache;
 
 type ManifestBuildMatrix = HashMap<String,Vec<Version>>;
 
-type RequirementMap = HashMap<String, Version>;
+pub type RequirementMap = HashMap<String, Version>;
+
+/// Everything that can go wrong loading or expanding a manifest. Parse errors
+/// format as `path:line:col: message`; I/O and `extends` errors carry the
+/// manifest's path. Matrix and cfg-predicate errors carry no path, only the
+/// offending template, key or predicate.
+#[derive(Debug)]
+pub enum ManifestError {
+    /// The manifest file could not be read.
+    Io { path: PathBuf, source: std::io::Error },
+    /// The manifest's YAML could not be deserialized.
+    Parse { path: Option<PathBuf>, line: usize, column: usize, source: serde_yaml::Error },
+    /// A matrix flavour's template failed to compile or render.
+    MatrixTemplate { template: String, source: mustache::Error },
+    /// A matrix flavour's template referenced a key not present in its matrix.
+    MatrixKey { key: String },
+    /// An `extends:` entry did not resolve to an existing manifest.
+    UnknownExtends { path: PathBuf, target: String },
+    /// An `extends:` chain referred back to a manifest already being loaded.
+    Cycle { path: PathBuf },
+    /// A `targetRequires` block's `cfg` predicate failed to parse.
+    TargetPredicate { cfg: String, message: String },
+}
+
+impl fmt::Display for ManifestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ManifestError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            ManifestError::Parse { path, line, column, source } => {
+                let path = path.as_ref().map(|p| p.display().to_string()).unwrap_or_else(|| "<string>".to_string());
+                write!(f, "{}:{}:{}: {}", path, line, column, source)
+            }
+            ManifestError::MatrixTemplate { template, source } => {
+                write!(f, "invalid matrix template '{}': {}", template, source)
+            }
+            ManifestError::MatrixKey { key } => {
+                write!(f, "matrix template references undefined key '{}'", key)
+            }
+            ManifestError::UnknownExtends { path, target } => {
+                write!(f, "{}: extends target '{}' does not exist", path.display(), target)
+            }
+            ManifestError::Cycle { path } => {
+                write!(f, "{}: cyclic `extends` chain detected", path.display())
+            }
+            ManifestError::TargetPredicate { cfg, message } => {
+                write!(f, "invalid cfg predicate '{}': {}", cfg, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ManifestError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ManifestError::Io { source, .. } => Some(source),
+            ManifestError::Parse { source, .. } => Some(source),
+            ManifestError::MatrixTemplate { source, .. } => Some(source),
+            ManifestError::MatrixKey { .. }
+            | ManifestError::UnknownExtends { .. }
+            | ManifestError::Cycle { .. }
+            | ManifestError::TargetPredicate { .. } => None,
+        }
+    }
+}
 
 /// Version models the possible values for a package version. Ideally,
 /// we would treat them all as strings. But, strongly typed languages parsing
@@ -21,7 +84,7 @@ type RequirementMap = HashMap<String, Version>;
 /// there is no way to coerce a type. I suppose that this is really a 
 /// problem with the yaml spec more than serde. But, for instance 
 /// 7 is an int, 7.1 is a float, and 7.3.2 is a string.  
-#[derive(Debug, PartialEq, PartialOrd, Deserialize)]
+#[derive(Debug, Clone, PartialEq, PartialOrd, Deserialize, Serialize)]
 #[serde(untagged)]
 pub enum Version {
     String(String),
@@ -44,7 +107,28 @@ impl fmt::Display for Version {
     }
 }
 
-#[derive(Debug,PartialEq,Deserialize)]
+impl Version {
+    /// Convert this raw version into a `semver::Version`, normalizing bare
+    /// ints/floats (e.g. `7` or `7.1`) into full `x.y.z` triples first.
+    pub fn to_semver(&self) -> Result<semver::Version, AnyError> {
+        let normalized = normalize_version_str(&self.to_string());
+        semver::Version::parse(&normalized)
+            .map_err(|e| anyhow!("'{}' is not a valid semver version: {}", self, e))
+    }
+}
+
+/// Pad a bare `x` or `x.y` version/requirement string out to `x.y.z` so it
+/// parses as valid semver; strings that already have two dots (or contain
+/// operators such as `>=1.2, <2.0`) are passed through unchanged.
+fn normalize_version_str(s: &str) -> String {
+    match s.matches('.').count() {
+        0 if s.chars().all(|c| c.is_ascii_digit()) => format!("{}.0.0", s),
+        1 if s.chars().all(|c| c.is_ascii_digit() || c == '.') => format!("{}.0", s),
+        _ => s.to_string(),
+    }
+}
+
+#[derive(Debug,PartialEq,Deserialize,Serialize)]
 pub struct RecipeInner {
     requires: Option<RequirementMap>,
     #[serde(rename = "loadRequires")]
@@ -55,14 +139,14 @@ pub struct RecipeInner {
 
 type RecipeMap = HashMap<String, RecipeInner>;
 
-#[derive(Debug,PartialEq,Deserialize)]
+#[derive(Debug,PartialEq,Deserialize,Serialize)]
 
 pub struct MatrixFlavour{
     name: String,
     matrix: ManifestBuildMatrix,
 }
 
-#[derive(Debug,PartialEq,Deserialize)]
+#[derive(Debug,PartialEq,Deserialize,Serialize)]
 pub struct BuildFlavour {
     name: String,
     recipes: RecipeMap,
@@ -74,9 +158,13 @@ pub struct Tools {
 
 type ExportsInner = HashMap<String, Vec<String>>;
 
-#[derive(Debug,PartialEq,Deserialize)]
+#[derive(Debug,PartialEq,Deserialize,Serialize)]
 #[serde(untagged)]
 pub enum Flavours {
+    // `Recipe` and `Simple` reject unknown fields so a `matrix:` key falls
+    // through to the `Matrix` variant instead of being silently dropped by
+    // serde_yaml's default of ignoring fields a struct doesn't declare.
+    #[serde(deny_unknown_fields)]
     Recipe{
         name: String,
         recipes: RecipeMap,
@@ -92,6 +180,7 @@ pub enum Flavours {
         platforms: Option<Vec<String>>,
         sites: Option<Vec<String>>,
     },
+    #[serde(deny_unknown_fields)]
     Simple{
         name: String,
         #[serde(rename = "loadRequires")]
@@ -101,7 +190,7 @@ pub enum Flavours {
         #[serde(rename = "testRequires")]
         test_requires: Option<RequirementMap>,
         #[serde(rename="systemRequires")]
-        system_requires: Option<RequirementMap>, 
+        system_requires: Option<RequirementMap>,
         supports: Option<Vec<String>>,
         platforms: Option<Vec<String>>,
         sites: Option<Vec<String>>,
@@ -122,7 +211,78 @@ pub enum Flavours {
     }
 }
 
-#[derive(Debug,PartialEq,Deserialize)]
+impl Flavours {
+    fn name(&self) -> &str {
+        match self {
+            Flavours::Recipe { name, .. } => name,
+            Flavours::Simple { name, .. } => name,
+            Flavours::Matrix { name, .. } => name,
+        }
+    }
+}
+
+// Key-merge two optional maps, with entries in `child` overriding entries of
+// the same key in `base`.
+fn merge_map<K: std::hash::Hash + Eq, V>(base: Option<HashMap<K, V>>, child: Option<HashMap<K, V>>) -> Option<HashMap<K, V>> {
+    match (base, child) {
+        (None, None) => None,
+        (Some(b), None) => Some(b),
+        (None, Some(c)) => Some(c),
+        (Some(mut b), Some(c)) => {
+            b.extend(c);
+            Some(b)
+        }
+    }
+}
+
+// Concatenate two optional string lists, dropping duplicates while keeping
+// first-seen order, and returning `None` if the result would be empty.
+fn merge_vec_dedup(base: Option<Vec<String>>, child: Option<Vec<String>>) -> Option<Vec<String>> {
+    let mut merged = base.unwrap_or_default();
+    merged.extend(child.unwrap_or_default());
+    if merged.is_empty() {
+        return None;
+    }
+    let mut seen = std::collections::HashSet::new();
+    merged.retain(|v| seen.insert(v.clone()));
+    Some(merged)
+}
+
+// Merge flavours by name: a child flavour of the same name replaces the
+// base's, and any flavour the child introduces is appended after.
+fn merge_flavours(base: Option<Vec<Flavours>>, child: Option<Vec<Flavours>>) -> Option<Vec<Flavours>> {
+    let base = base.unwrap_or_default();
+    let mut child = child.unwrap_or_default();
+    if base.is_empty() && child.is_empty() {
+        return None;
+    }
+    let mut result = Vec::with_capacity(base.len() + child.len());
+    for base_flavour in base {
+        match child.iter().position(|f| f.name() == base_flavour.name()) {
+            Some(pos) => result.push(child.remove(pos)),
+            None => result.push(base_flavour),
+        }
+    }
+    result.extend(child);
+    Some(result)
+}
+
+// Concatenate target-requirement blocks, base first so a child block with
+// the same `cfg` is evaluated later in `requires_for` and overrides it.
+fn merge_target_requires(
+    base: Option<Vec<TargetRequirementBlock>>,
+    child: Option<Vec<TargetRequirementBlock>>,
+) -> Option<Vec<TargetRequirementBlock>> {
+    let mut merged = base.unwrap_or_default();
+    merged.extend(child.unwrap_or_default());
+    if merged.is_empty() {
+        None
+    } else {
+        Some(merged)
+    }
+}
+
+#[derive(Debug,PartialEq,Deserialize,Serialize)]
 pub struct Manifest {
     name: String,
     version: String,
@@ -140,23 +300,115 @@ pub struct Manifest {
     sites: Option<Vec<String>>,
     recipes: Option<RecipeMap>,
     flavours: Option<Vec<Flavours>>,
-    exports: Option<ExportsInner>
+    exports: Option<ExportsInner>,
+    #[serde(rename = "targetRequires")]
+    target_requires: Option<Vec<TargetRequirementBlock>>,
+    /// Base manifest paths, relative to this manifest, to merge underneath it.
+    extends: Option<Vec<String>>,
+}
+
+/// A requirement map that only applies when `cfg` matches the caller's
+/// [`Target`], analogous to Cargo's `[target.'cfg(...)'.dependencies]`.
+#[derive(Debug,PartialEq,Deserialize,Serialize)]
+pub struct TargetRequirementBlock {
+    cfg: String,
+    requires: RequirementMap,
 }
 
 impl Manifest {
-    /// Generate a Manifest given its path on disk, assuming it is valid.Otherwise, error.
-    pub fn from_path<I>(path: I) -> Result<Manifest, AnyError> where I: Into<PathBuf> {
-        let manifest_path = path.into();
-        let contents = std::fs::read_to_string(manifest_path)?;
-        let manifest: Manifest = serde_yaml::from_str(&contents)?;
-        Ok(manifest)
+    /// Generate a Manifest given its path on disk, resolving and merging any
+    /// `extends:` base manifests underneath it. Otherwise, error.
+    pub fn from_path<I>(path: I) -> Result<Manifest, ManifestError> where I: Into<PathBuf> {
+        Self::load_with_visited(path.into(), &mut Vec::new())
+    }
+
+    /// Generate a Manifest given its path on disk, without resolving `extends:`.
+    pub fn from_path_no_inherit<I>(path: I) -> Result<Manifest, ManifestError> where I: Into<PathBuf> {
+        let path = path.into();
+        let contents = std::fs::read_to_string(&path)
+            .map_err(|e| ManifestError::Io { path: path.clone(), source: e })?;
+        Self::parse(&contents, Some(path))
     }
 
     /// Generate a Manifest instance from a &str, assuming it is valid. Otherwise, error.
-    pub fn from_str<I>(contents: I) -> Result<Manifest, AnyError> where I: AsRef<str> {
-        let contents = contents.as_ref();
-        let manifest : Manifest = serde_yaml::from_str(contents)?;
-        Ok(manifest)
+    /// `extends:` is not resolved, since there is no base path to resolve it against.
+    pub fn from_str<I>(contents: I) -> Result<Manifest, ManifestError> where I: AsRef<str> {
+        Self::parse(contents.as_ref(), None)
+    }
+
+    fn parse(contents: &str, path: Option<PathBuf>) -> Result<Manifest, ManifestError> {
+        serde_yaml::from_str(contents).map_err(|e| {
+            let (line, column) = e.location().map(|l| (l.line(), l.column())).unwrap_or((0, 0));
+            ManifestError::Parse { path, line, column, source: e }
+        })
+    }
+
+    fn load_with_visited(path: PathBuf, visited: &mut Vec<PathBuf>) -> Result<Manifest, ManifestError> {
+        let canonical = path.canonicalize()
+            .map_err(|e| ManifestError::Io { path: path.clone(), source: e })?;
+        if visited.contains(&canonical) {
+            return Err(ManifestError::Cycle { path: canonical });
+        }
+        visited.push(canonical);
+
+        let manifest = Self::from_path_no_inherit(path.as_path())?;
+        let merged = match manifest.extends.clone() {
+            Some(ref bases) if !bases.is_empty() => {
+                let base_dir = path.parent().unwrap_or_else(|| std::path::Path::new("."));
+                let mut merged_base: Option<Manifest> = None;
+                for base in bases {
+                    let base_path = base_dir.join(base);
+                    if !base_path.exists() {
+                        return Err(ManifestError::UnknownExtends { path: path.clone(), target: base.clone() });
+                    }
+                    let base_manifest = Self::load_with_visited(base_path, visited)?;
+                    merged_base = Some(match merged_base {
+                        None => base_manifest,
+                        Some(acc) => Self::merge(acc, base_manifest),
+                    });
+                }
+                Self::merge(merged_base.expect("extends was non-empty"), manifest)
+            }
+            _ => manifest,
+        };
+
+        visited.pop();
+        Ok(merged)
+    }
+
+    /// Overlay `child` on top of `base`: scalars and keyed maps favour the
+    /// child, list fields are concatenated and de-duplicated, and flavours
+    /// merge by name with the child's flavour replacing the base's.
+    fn merge(base: Manifest, child: Manifest) -> Manifest {
+        Manifest {
+            name: child.name,
+            version: child.version,
+            supports: merge_vec_dedup(base.supports, child.supports),
+            load_requires: merge_map(base.load_requires, child.load_requires),
+            build_requires: merge_map(base.build_requires, child.build_requires),
+            test_requires: merge_map(base.test_requires, child.test_requires),
+            system_requires: merge_map(base.system_requires, child.system_requires),
+            requires: merge_map(base.requires, child.requires),
+            platforms: merge_vec_dedup(base.platforms, child.platforms),
+            sites: merge_vec_dedup(base.sites, child.sites),
+            recipes: merge_map(base.recipes, child.recipes),
+            flavours: merge_flavours(base.flavours, child.flavours),
+            exports: merge_map(base.exports, child.exports),
+            target_requires: merge_target_requires(base.target_requires, child.target_requires),
+            extends: None,
+        }
+    }
+
+    /// Serialize this manifest back to a YAML string.
+    pub fn to_yaml(&self) -> Result<String, AnyError> {
+        Ok(serde_yaml::to_string(self)?)
+    }
+
+    /// Serialize this manifest and write it to `path` as YAML.
+    pub fn write_to_path<I: Into<PathBuf>>(&self, path: I) -> Result<(), AnyError> {
+        let path = path.into();
+        std::fs::write(path, self.to_yaml()?)?;
+        Ok(())
     }
 
     /// Retrieve the name of the manifest.
@@ -169,6 +421,31 @@ impl Manifest {
         self.version.as_str()
     }
 
+    /// Retrieve the unconditional `requires` map, if any.
+    pub fn requires(&self) -> Option<&RequirementMap> {
+        self.requires.as_ref()
+    }
+
+    /// Retrieve the `loadRequires` map, if any.
+    pub fn load_requires(&self) -> Option<&RequirementMap> {
+        self.load_requires.as_ref()
+    }
+
+    /// Retrieve the `buildRequires` map, if any.
+    pub fn build_requires(&self) -> Option<&RequirementMap> {
+        self.build_requires.as_ref()
+    }
+
+    /// Retrieve the `testRequires` map, if any.
+    pub fn test_requires(&self) -> Option<&RequirementMap> {
+        self.test_requires.as_ref()
+    }
+
+    /// Retrieve the `systemRequires` map, if any.
+    pub fn system_requires(&self) -> Option<&RequirementMap> {
+        self.system_requires.as_ref()
+    }
+
     /// Retrieve the tools exported by the manifest.
     pub fn tools(&self) -> Vec<&str> {
         if let Some(ref exports) = self.exports {
@@ -204,7 +481,7 @@ impl Manifest {
         }
     }
     /// Retrieve the flavors defined in the manifest.
-    pub fn flavors(&self) -> Result<Vec<String>, AnyError> {
+    pub fn flavors(&self) -> Result<Vec<String>, ManifestError> {
         let mut flavors = Vec::new();
         if self.requires.is_some() || self.recipes.is_some() {
             flavors.push("^".to_string());
@@ -221,81 +498,736 @@ impl Manifest {
                             keys.push(k.as_str());
                             par.push( v.iter().map(|c| c).collect::<Vec<_>>() );
                         }
-                        let mut expand = match keys.len() {
-                            1 =>Self::one(name.as_str(), &keys, &par[0]),
-                            2 => Self::two(name.as_str(), &keys, &par[0], &par[1]),
-                            3 => Self::three(name.as_str(), &keys, &par[0], &par[1], &par[2]),
-                            4 => Self::four(name.as_str(), &keys, &par[0], &par[1], &par[2], &par[4]),
-                            _ => Err(anyhow!("Cannot expand template with more than four arguments"))
-                        }?;
-                        
-                        //let mut rval = Self::two(name.as_str(), &keys, &par[0], &par[1])?;
+                        let mut expand = Self::expand_matrix(name.as_str(), &keys, &par)?;
                         flavors.append(&mut expand);
                     }
-                
+
                 }
             }
         }
         Ok(flavors)
     }
 
-    // Iterate over single key
-    fn one(template: &str, keys: &Vec<&str>, one: &Vec<&Version>) -> Result<Vec<String>, AnyError> {
-        let  mut results = Vec::new();
-        for  i in one {
-            let map = MapBuilder::new()
-            .insert_str(keys[0], i.to_string().as_str())
-            .build();
-            let rtemplate = mustache::compile_str(template.replace("row.","").as_str())?;
-            let r = rtemplate.render_data_to_string( &map)?;
+    // Expand a matrix flavour template into one rendered string per combination
+    // of its keys, via an odometer-style cartesian product over `values`.
+    fn expand_matrix(template: &str, keys: &[&str], values: &[Vec<&Version>]) -> Result<Vec<String>, ManifestError> {
+        let mut combinations: Vec<Vec<&Version>> = vec![Vec::new()];
+        for dimension in values {
+            let mut next = Vec::new();
+            for combo in &combinations {
+                for value in dimension {
+                    let mut extended = combo.clone();
+                    extended.push(value);
+                    next.push(extended);
+                }
+            }
+            combinations = next;
+        }
+
+        let stripped = template.replace("row.", "");
+        if let Some(key) = find_undeclared_key(&stripped, keys) {
+            return Err(ManifestError::MatrixKey { key });
+        }
+        let rtemplate = mustache::compile_str(stripped.as_str())
+            .map_err(|e| ManifestError::MatrixTemplate { template: template.to_string(), source: e })?;
+        let mut results = Vec::new();
+        for combo in combinations {
+            let mut builder = MapBuilder::new();
+            for (key, value) in keys.iter().zip(combo.iter()) {
+                builder = builder.insert_str(*key, value.to_string().as_str());
+            }
+            let map = builder.build();
+            let r = rtemplate.render_data_to_string(&map)
+                .map_err(|e| ManifestError::MatrixTemplate { template: template.to_string(), source: e })?;
             results.push(r);
         }
         Ok(results)
     }
-    
-    // iterate over two keys
-    fn two(template: &str, keys: &Vec<&str>, one: &Vec<&Version>, two: &Vec<&Version>) -> Result<Vec<String>, AnyError> {
-        let  mut results = Vec::new();
-        for ( i,j) in iproduct!(one,two) {
-            let map = MapBuilder::new()
-            .insert_str(keys[0], i.to_string().as_str())
-            .insert_str(keys[1], j.to_string().as_str())
-            .build();
-            let rtemplate = mustache::compile_str(template.replace("row.","").as_str())?;
-            let r = rtemplate.render_data_to_string( &map)?;
-            results.push(r);
+
+    // Gather every (name, requirement) pair across `requires`, `loadRequires`,
+    // `buildRequires`, `testRequires` and `systemRequires`.
+    fn requirement_entries(&self) -> Vec<(&str, &Version)> {
+        let mut entries = Vec::new();
+        for map in [
+            &self.requires,
+            &self.load_requires,
+            &self.build_requires,
+            &self.test_requires,
+            &self.system_requires,
+        ] {
+            for (name, version) in map.iter().flatten() {
+                entries.push((name.as_str(), version));
+            }
         }
-        Ok(results)
+        entries
     }
 
-    fn three(template: &str, keys: &Vec<&str>, one: &Vec<&Version>, two: &Vec<&Version>, three: &Vec<&Version>) -> Result<Vec<String>,AnyError> {
-        let  mut results = Vec::new();
-        for ( i,j,k) in iproduct!(one,two,three) {
-            let map = MapBuilder::new()
-            .insert_str(keys[0], i.to_string().as_str())
-            .insert_str(keys[1], j.to_string().as_str())
-            .insert_str(keys[2], k.to_string().as_str())
-            .build();
-            let rtemplate = mustache::compile_str(template.replace("row.","").as_str())?;
-            let r = rtemplate.render_data_to_string( &map)?;
-            results.push(r);
+    /// Check this manifest's requirements against a caller-supplied set of
+    /// available package versions, returning the `(name, version)` pairs that
+    /// satisfy their requirement. Errors with the offending name/constraint
+    /// on the first requirement that cannot be satisfied.
+    pub fn resolve(&self, provided: &HashMap<String, semver::Version>) -> Result<Vec<(String, semver::Version)>, AnyError> {
+        let mut resolved = Vec::new();
+        for (name, version) in self.requirement_entries() {
+            let normalized = normalize_version_str(&version.to_string());
+            let req = semver::VersionReq::parse(&normalized)
+                .map_err(|e| anyhow!("invalid requirement '{}' for package '{}': {}", version, name, e))?;
+            let available = provided.get(name)
+                .ok_or_else(|| anyhow!("no version provided for required package '{}'", name))?;
+            if !req.matches(available) {
+                return Err(anyhow!(
+                    "requirement '{}' for package '{}' not satisfied by available version {}",
+                    version, name, available
+                ));
+            }
+            resolved.push((name.to_string(), available.clone()));
         }
-        Ok(results)
+        Ok(resolved)
     }
 
-    fn four(template: &str, keys: &Vec<&str>, one: &Vec<&Version>, two: &Vec<&Version>, three: &Vec<&Version>, four: &Vec<&Version>) -> Result<Vec<String>,AnyError> {
-        let  mut results = Vec::new();
-        for ( i,j,k,l) in iproduct!(one,two,three, four) {
-            let map = MapBuilder::new()
-            .insert_str(keys[0], i.to_string().as_str())
-            .insert_str(keys[1], j.to_string().as_str())
-            .insert_str(keys[2], k.to_string().as_str())
-            .insert_str(keys[3], l.to_string().as_str())
-            .build();
-            let rtemplate = mustache::compile_str(template.replace("row.","").as_str())?;
-            let r = rtemplate.render_data_to_string( &map)?;
-            results.push(r);
+    /// Merge the unconditional `requires` with every `targetRequires` block
+    /// whose `cfg` predicate matches `target`, later blocks overriding
+    /// earlier ones (and the unconditional set) for a given package name.
+    /// Errors if any block's `cfg` predicate fails to parse, rather than
+    /// silently dropping that block's requirements.
+    pub fn requires_for(&self, target: &Target) -> Result<RequirementMap, ManifestError> {
+        let mut merged: RequirementMap = self.requires.clone().unwrap_or_default();
+        if let Some(blocks) = &self.target_requires {
+            for block in blocks {
+                let predicate = Predicate::parse(&block.cfg)
+                    .map_err(|message| ManifestError::TargetPredicate { cfg: block.cfg.clone(), message })?;
+                if predicate.matches(target) {
+                    for (name, version) in &block.requires {
+                        merged.insert(name.clone(), version.clone());
+                    }
+                }
+            }
         }
-        Ok(results)
+        Ok(merged)
+    }
+}
+
+/// A descriptor of the platform a manifest is being evaluated for, e.g. the
+/// `target_os`/`target_arch` of the host and the site it's being built at.
+/// Arbitrary extra facts may be supplied for bespoke predicates.
+#[derive(Debug, Clone, Default)]
+pub struct Target {
+    facts: HashMap<String, String>,
+}
+
+impl Target {
+    pub fn new() -> Self {
+        Target { facts: HashMap::new() }
+    }
+
+    /// Build a target descriptor from the common os/arch/site facts. Also
+    /// sets a `platform` fact of `"{os}-{arch}"` so bare platform-triple
+    /// predicates (`Predicate::Literal`) have something to match against
+    /// without the caller setting it by hand.
+    pub fn for_os_arch_site<I: Into<String>>(os: I, arch: I, site: I) -> Self {
+        let os = os.into();
+        let arch = arch.into();
+        let platform = format!("{}-{}", os, arch);
+        Target::new()
+            .with_fact("target_os", os)
+            .with_fact("target_arch", arch)
+            .with_fact("site", site)
+            .with_fact("platform", platform)
+    }
+
+    pub fn with_fact<K: Into<String>, V: Into<String>>(mut self, key: K, value: V) -> Self {
+        self.facts.insert(key.into(), value.into());
+        self
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.facts.get(key).map(|v| v.as_str())
+    }
+}
+
+/// A parsed `cfg(...)`-style predicate, or a bare platform triple.
+#[derive(Debug, PartialEq)]
+enum Predicate {
+    All(Vec<Predicate>),
+    Any(Vec<Predicate>),
+    Not(Box<Predicate>),
+    Eq(String, String),
+    Literal(String),
+}
+
+impl Predicate {
+    fn parse(expr: &str) -> Result<Predicate, String> {
+        let trimmed = expr.trim();
+        if let Some(inner) = trimmed.strip_prefix("cfg(").and_then(|s| s.strip_suffix(')')) {
+            let mut chars = inner.chars().peekable();
+            let predicate = Self::parse_expr(&mut chars)?;
+            skip_ws(&mut chars);
+            if chars.peek().is_some() {
+                return Err(format!("unexpected trailing characters in cfg expression: {}", expr));
+            }
+            Ok(predicate)
+        } else {
+            Ok(Predicate::Literal(trimmed.to_string()))
+        }
+    }
+
+    fn parse_expr(chars: &mut std::iter::Peekable<std::str::Chars>) -> Result<Predicate, String> {
+        skip_ws(chars);
+        let ident = parse_ident(chars);
+        skip_ws(chars);
+        match chars.peek() {
+            Some('(') => {
+                chars.next();
+                let mut args = Vec::new();
+                loop {
+                    skip_ws(chars);
+                    if chars.peek() == Some(&')') {
+                        break;
+                    }
+                    args.push(Self::parse_expr(chars)?);
+                    skip_ws(chars);
+                    match chars.peek() {
+                        Some(',') => { chars.next(); }
+                        Some(')') => break,
+                        other => return Err(format!("expected ',' or ')' in cfg expression, found {:?}", other)),
+                    }
+                }
+                skip_ws(chars);
+                if chars.next() != Some(')') {
+                    return Err("unterminated cfg expression, expected ')'".to_string());
+                }
+                match ident.as_str() {
+                    "all" => Ok(Predicate::All(args)),
+                    "any" => Ok(Predicate::Any(args)),
+                    "not" if args.len() == 1 => Ok(Predicate::Not(Box::new(args.into_iter().next().unwrap()))),
+                    "not" => Err(format!("not() expects exactly one argument, got {}", args.len())),
+                    other => Err(format!("unknown cfg predicate '{}'", other)),
+                }
+            }
+            Some('=') => {
+                chars.next();
+                skip_ws(chars);
+                let value = parse_quoted_or_bare(chars)?;
+                Ok(Predicate::Eq(ident, value))
+            }
+            _ => Ok(Predicate::Literal(ident)),
+        }
+    }
+
+    fn matches(&self, target: &Target) -> bool {
+        match self {
+            Predicate::All(preds) => preds.iter().all(|p| p.matches(target)),
+            Predicate::Any(preds) => preds.iter().any(|p| p.matches(target)),
+            Predicate::Not(pred) => !pred.matches(target),
+            Predicate::Eq(key, value) => target.get(key) == Some(value.as_str()),
+            Predicate::Literal(triple) => target.get("platform") == Some(triple.as_str()),
+        }
+    }
+}
+
+fn parse_ident(chars: &mut std::iter::Peekable<std::str::Chars>) -> String {
+    let mut ident = String::new();
+    while let Some(&c) = chars.peek() {
+        if c.is_alphanumeric() || c == '_' || c == '-' || c == '.' {
+            ident.push(c);
+            chars.next();
+        } else {
+            break;
+        }
+    }
+    ident
+}
+
+fn parse_quoted_or_bare(chars: &mut std::iter::Peekable<std::str::Chars>) -> Result<String, String> {
+    if chars.peek() == Some(&'"') {
+        chars.next();
+        let mut value = String::new();
+        loop {
+            match chars.next() {
+                Some('"') => break,
+                Some(c) => value.push(c),
+                None => return Err("unterminated string literal in cfg expression".to_string()),
+            }
+        }
+        Ok(value)
+    } else {
+        Ok(parse_ident(chars))
+    }
+}
+
+// Scan a (row.-stripped) mustache template for `{{ident}}`/`{{{ident}}}`
+// placeholders and return the first one that isn't one of the matrix's
+// declared `keys`.
+fn find_undeclared_key(template: &str, keys: &[&str]) -> Option<String> {
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        rest = &rest[start + 2..];
+        // Triple-mustache (`{{{foo}}}`) leaves an extra brace on each side.
+        let triple = rest.starts_with('{');
+        if triple {
+            rest = &rest[1..];
+        }
+        let end = rest.find("}}")?;
+        let raw = rest[..end].trim();
+        rest = &rest[end + 2..];
+        if triple && rest.starts_with('}') {
+            rest = &rest[1..];
+        }
+        let name = raw.trim_start_matches(['&', '#', '/', '^', '!']).trim();
+        if name.is_empty() || raw.starts_with('!') {
+            continue;
+        }
+        if !keys.contains(&name) {
+            return Some(name.to_string());
+        }
+    }
+    None
+}
+
+fn skip_ws(chars: &mut std::iter::Peekable<std::str::Chars>) {
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // One fixture per `Flavours` variant (plus `targetRequires` and `extends`,
+    // and each `Version` shape), so the `#[serde(untagged)]` enums are
+    // exercised on the way out as well as the way in.
+    const FIXTURE_BASIC: &str = r#"
+name: core
+version: "1.2.3"
+requires:
+  libfoo: 7
+  libbar: 7.1
+  libbaz: "2.3.4"
+platforms:
+  - linux
+  - darwin
+exports:
+  tools:
+    - mytool
+extends:
+  - base.yaml
+"#;
+
+    const FIXTURE_RECIPE_FLAVOUR: &str = r#"
+name: tools
+version: "2.0.0"
+recipes:
+  build:
+    requires:
+      libfoo: "1.0.0"
+    steps:
+      - make
+flavours:
+  - name: ci
+    recipes:
+      test:
+        loadRequires:
+          libbar: 3
+        steps:
+          - test
+    buildRequires:
+      libbaz: "0.9.0"
+"#;
+
+    const FIXTURE_SIMPLE_FLAVOUR_AND_TARGET_REQUIRES: &str = r#"
+name: widgets
+version: "0.3.1"
+flavours:
+  - name: debug
+    loadRequires:
+      libqux: 1.5
+    sites:
+      - site-a
+targetRequires:
+  - cfg: 'cfg(target_os = "linux")'
+    requires:
+      liblinux: "1.0.0"
+"#;
+
+    const FIXTURE_MATRIX_FLAVOUR: &str = r#"
+name: matrixy
+version: "0.1.0"
+flavours:
+  - name: build
+    matrix:
+      os:
+        - linux
+        - windows
+      arch:
+        - x86_64
+"#;
+
+    const FIXTURES: &[&str] = &[
+        FIXTURE_BASIC,
+        FIXTURE_RECIPE_FLAVOUR,
+        FIXTURE_SIMPLE_FLAVOUR_AND_TARGET_REQUIRES,
+        FIXTURE_MATRIX_FLAVOUR,
+    ];
+
+    #[test]
+    fn manifests_round_trip_through_yaml() {
+        for fixture in FIXTURES {
+            let manifest = Manifest::from_str(fixture).expect("fixture should parse");
+            let yaml = manifest.to_yaml().expect("manifest should serialize");
+            let reparsed = Manifest::from_str(&yaml).expect("serialized yaml should reparse");
+            assert_eq!(manifest, reparsed, "round-trip mismatch for fixture:\n{}", fixture);
+        }
+
+        // Round-trip equality alone doesn't prove FIXTURE_MATRIX_FLAVOUR took
+        // the Matrix branch of the untagged enum — a consistent mis-parse as
+        // Simple on both sides would pass just as silently. Pin the variant.
+        let matrix_manifest = Manifest::from_str(FIXTURE_MATRIX_FLAVOUR).expect("fixture should parse");
+        match &matrix_manifest.flavours.as_ref().unwrap()[0] {
+            Flavours::Matrix { .. } => {}
+            other => panic!("expected FIXTURE_MATRIX_FLAVOUR to parse as Flavours::Matrix, got {:?}", other),
+        }
+    }
+
+    const FIXTURE_MATRIX_EXPANSION: &str = r#"
+name: matrixy
+version: "0.1.0"
+flavours:
+  - name: "build-{{os}}-{{arch}}"
+    matrix:
+      os:
+        - linux
+        - windows
+      arch:
+        - x86_64
+"#;
+
+    #[test]
+    fn matrix_flavour_parses_as_matrix_variant_and_expands() {
+        let manifest = Manifest::from_str(FIXTURE_MATRIX_EXPANSION).expect("fixture should parse");
+        match &manifest.flavours.as_ref().unwrap()[0] {
+            Flavours::Matrix { .. } => {}
+            other => panic!("expected Flavours::Matrix, got {:?}", other),
+        }
+
+        let expanded: std::collections::HashSet<String> =
+            manifest.flavors().expect("flavors should expand").into_iter().collect();
+        let expected: std::collections::HashSet<String> = [
+            "build-linux-x86_64".to_string(),
+            "build-windows-x86_64".to_string(),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(expanded, expected);
+    }
+
+    #[test]
+    fn normalize_version_str_pads_bare_numbers_only() {
+        assert_eq!(normalize_version_str("7"), "7.0.0");
+        assert_eq!(normalize_version_str("7.1"), "7.1.0");
+        assert_eq!(normalize_version_str("7.1.2"), "7.1.2");
+        assert_eq!(normalize_version_str(">=1.2, <2.0"), ">=1.2, <2.0");
+    }
+
+    #[test]
+    fn version_to_semver_normalizes_each_variant() {
+        assert_eq!(Version::Int(7).to_semver().unwrap(), semver::Version::parse("7.0.0").unwrap());
+        assert_eq!(Version::Float(7.1).to_semver().unwrap(), semver::Version::parse("7.1.0").unwrap());
+        assert_eq!(
+            Version::String("2.3.4".to_string()).to_semver().unwrap(),
+            semver::Version::parse("2.3.4").unwrap()
+        );
+    }
+
+    #[test]
+    fn resolve_matches_range_requirements_and_reports_failures() {
+        let manifest = Manifest::from_str(
+            r#"
+name: app
+version: "1.0.0"
+requires:
+  libfoo: ">=1.2, <2.0"
+"#,
+        )
+        .unwrap();
+
+        let mut provided = HashMap::new();
+        provided.insert("libfoo".to_string(), semver::Version::parse("1.5.0").unwrap());
+        let resolved = manifest.resolve(&provided).expect("requirement should resolve");
+        assert_eq!(resolved, vec![("libfoo".to_string(), semver::Version::parse("1.5.0").unwrap())]);
+
+        // Package missing entirely from the provided set.
+        let empty: HashMap<String, semver::Version> = HashMap::new();
+        assert!(manifest.resolve(&empty).is_err());
+
+        // Package present but outside the requirement's range.
+        let mut unsatisfied = HashMap::new();
+        unsatisfied.insert("libfoo".to_string(), semver::Version::parse("2.5.0").unwrap());
+        assert!(manifest.resolve(&unsatisfied).is_err());
+    }
+
+    #[test]
+    fn predicate_parses_all_any_not_and_literals() {
+        assert_eq!(
+            Predicate::parse(r#"cfg(target_os = "linux")"#).unwrap(),
+            Predicate::Eq("target_os".to_string(), "linux".to_string())
+        );
+        assert_eq!(
+            Predicate::parse(r#"cfg(not(target_os = "windows"))"#).unwrap(),
+            Predicate::Not(Box::new(Predicate::Eq("target_os".to_string(), "windows".to_string())))
+        );
+        assert_eq!(
+            Predicate::parse(r#"cfg(all(target_os = "linux", target_arch = "x86_64"))"#).unwrap(),
+            Predicate::All(vec![
+                Predicate::Eq("target_os".to_string(), "linux".to_string()),
+                Predicate::Eq("target_arch".to_string(), "x86_64".to_string()),
+            ])
+        );
+        assert_eq!(
+            Predicate::parse("cfg(any(target_os = linux, target_os = darwin))").unwrap(),
+            Predicate::Any(vec![
+                Predicate::Eq("target_os".to_string(), "linux".to_string()),
+                Predicate::Eq("target_os".to_string(), "darwin".to_string()),
+            ])
+        );
+        assert_eq!(
+            Predicate::parse("linux-x86_64").unwrap(),
+            Predicate::Literal("linux-x86_64".to_string())
+        );
+    }
+
+    #[test]
+    fn predicate_parse_rejects_malformed_expressions() {
+        assert!(Predicate::parse(r#"cfg(not(a = "1", b = "2"))"#).is_err());
+        assert!(Predicate::parse(r#"cfg(bogus(a = "1"))"#).is_err());
+        assert!(Predicate::parse(r#"cfg(not(target_os = "linux")"#).is_err());
+        assert!(Predicate::parse(r#"cfg(target_os = "linux" extra)"#).is_err());
+    }
+
+    #[test]
+    fn predicate_matches_against_target_facts() {
+        let target = Target::for_os_arch_site("linux", "x86_64", "site-a");
+        assert!(Predicate::parse(r#"cfg(target_os = "linux")"#).unwrap().matches(&target));
+        assert!(!Predicate::parse(r#"cfg(target_os = "windows")"#).unwrap().matches(&target));
+        assert!(Predicate::parse("linux-x86_64").unwrap().matches(&target));
+        assert!(Predicate::parse(r#"cfg(all(target_os = "linux", target_arch = "x86_64"))"#)
+            .unwrap()
+            .matches(&target));
+        assert!(Predicate::parse(r#"cfg(not(target_os = "windows"))"#).unwrap().matches(&target));
+    }
+
+    #[test]
+    fn requires_for_merges_unconditional_and_matching_target_blocks_with_override() {
+        let manifest = Manifest::from_str(
+            r#"
+name: widgets
+version: "1.0.0"
+requires:
+  libfoo: "1.0.0"
+targetRequires:
+  - cfg: 'cfg(target_os = "linux")'
+    requires:
+      libbar: "1.0.0"
+  - cfg: 'cfg(target_os = "linux")'
+    requires:
+      libbar: "2.0.0"
+  - cfg: 'cfg(target_os = "windows")'
+    requires:
+      libbaz: "1.0.0"
+"#,
+        )
+        .unwrap();
+
+        let linux = Target::for_os_arch_site("linux", "x86_64", "site-a");
+        let merged = manifest.requires_for(&linux).unwrap();
+        assert_eq!(merged.get("libfoo"), Some(&Version::String("1.0.0".to_string())));
+        // The second linux block overrides the first for the same package.
+        assert_eq!(merged.get("libbar"), Some(&Version::String("2.0.0".to_string())));
+        assert!(!merged.contains_key("libbaz"));
+
+        let windows = Target::for_os_arch_site("windows", "x86_64", "site-a");
+        let merged = manifest.requires_for(&windows).unwrap();
+        assert_eq!(merged.get("libbaz"), Some(&Version::String("1.0.0".to_string())));
+        assert!(!merged.contains_key("libbar"));
+    }
+
+    #[test]
+    fn requires_for_propagates_malformed_cfg_errors() {
+        let manifest = Manifest::from_str(
+            r#"
+name: widgets
+version: "1.0.0"
+targetRequires:
+  - cfg: 'cfg(not(target_os = "linux")'
+    requires:
+      libbar: "1.0.0"
+"#,
+        )
+        .unwrap();
+
+        let target = Target::for_os_arch_site("linux", "x86_64", "site-a");
+        assert!(manifest.requires_for(&target).is_err());
+    }
+
+    fn temp_dir_for(name: &str) -> PathBuf {
+        let mut dir = std::env::temp_dir();
+        dir.push(format!("maniparse-test-{}-{}", std::process::id(), name));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn extends_detects_cycles() {
+        let dir = temp_dir_for("extends-cycle");
+        std::fs::write(dir.join("a.yaml"), "name: a\nversion: \"1.0.0\"\nextends:\n  - b.yaml\n").unwrap();
+        std::fs::write(dir.join("b.yaml"), "name: b\nversion: \"1.0.0\"\nextends:\n  - a.yaml\n").unwrap();
+
+        let err = Manifest::from_path(dir.join("a.yaml")).unwrap_err();
+        assert!(matches!(err, ManifestError::Cycle { .. }), "expected Cycle, got {:?}", err);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn extends_resolves_a_diamond_without_a_false_cycle() {
+        let dir = temp_dir_for("extends-diamond");
+        std::fs::write(dir.join("base.yaml"), "name: base\nversion: \"1.0.0\"\nrequires:\n  libbase: \"1.0.0\"\n").unwrap();
+        std::fs::write(
+            dir.join("left.yaml"),
+            "name: left\nversion: \"1.0.0\"\nextends:\n  - base.yaml\nrequires:\n  libleft: \"1.0.0\"\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("right.yaml"),
+            "name: right\nversion: \"1.0.0\"\nextends:\n  - base.yaml\nrequires:\n  libright: \"1.0.0\"\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("top.yaml"),
+            "name: top\nversion: \"1.0.0\"\nextends:\n  - left.yaml\n  - right.yaml\n",
+        )
+        .unwrap();
+
+        let manifest = Manifest::from_path(dir.join("top.yaml")).expect("diamond extends should resolve");
+        let requires = manifest.requires().expect("requires should be merged in");
+        assert!(requires.contains_key("libbase"));
+        assert!(requires.contains_key("libleft"));
+        assert!(requires.contains_key("libright"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn extends_merges_keys_dedups_lists_and_merges_flavours_by_name() {
+        let dir = temp_dir_for("extends-merge");
+        std::fs::write(
+            dir.join("base.yaml"),
+            r#"
+name: base
+version: "1.0.0"
+requires:
+  libfoo: "1.0.0"
+platforms:
+  - linux
+flavours:
+  - name: a
+    sites:
+      - base-site
+  - name: b
+    sites:
+      - site-b
+"#,
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("child.yaml"),
+            r#"
+name: child
+version: "2.0.0"
+extends:
+  - base.yaml
+requires:
+  libbar: "2.0.0"
+platforms:
+  - linux
+  - darwin
+flavours:
+  - name: a
+    sites:
+      - child-site
+  - name: c
+    sites:
+      - site-c
+"#,
+        )
+        .unwrap();
+
+        let manifest = Manifest::from_path(dir.join("child.yaml")).expect("extends should resolve");
+
+        // The child's own scalars win outright.
+        assert_eq!(manifest.name(), "child");
+        assert_eq!(manifest.version(), "2.0.0");
+
+        // Keyed maps merge, child keys joining base's rather than replacing it.
+        let requires = manifest.requires().unwrap();
+        assert_eq!(requires.get("libfoo"), Some(&Version::String("1.0.0".to_string())));
+        assert_eq!(requires.get("libbar"), Some(&Version::String("2.0.0".to_string())));
+
+        // Lists concatenate with duplicates dropped, base entries first.
+        assert_eq!(manifest.platforms, Some(vec!["linux".to_string(), "darwin".to_string()]));
+
+        // Flavours merge by name: child's "a" replaces base's, base's "b" is
+        // kept, and child's new "c" is appended, in that order.
+        let flavours = manifest.flavours.as_ref().unwrap();
+        assert_eq!(flavours.len(), 3);
+        match &flavours[0] {
+            Flavours::Simple { name, sites, .. } => {
+                assert_eq!(name, "a");
+                assert_eq!(sites.as_ref().unwrap(), &vec!["child-site".to_string()]);
+            }
+            other => panic!("expected Flavours::Simple 'a', got {:?}", other),
+        }
+        assert_eq!(flavours[1].name(), "b");
+        assert_eq!(flavours[2].name(), "c");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn parse_error_formats_as_path_line_col_message_with_a_source() {
+        let dir = temp_dir_for("parse-error-format");
+        let path = dir.join("bad.yaml");
+        std::fs::write(&path, "name: [unterminated\n").unwrap();
+
+        let err = Manifest::from_path_no_inherit(path.as_path()).unwrap_err();
+        match &err {
+            ManifestError::Parse { line, column, .. } => {
+                let rendered = err.to_string();
+                let expected_prefix = format!("{}:{}:{}: ", path.display(), line, column);
+                assert!(
+                    rendered.starts_with(&expected_prefix),
+                    "expected '{}' to start with '{}'",
+                    rendered,
+                    expected_prefix
+                );
+            }
+            other => panic!("expected ManifestError::Parse, got {:?}", other),
+        }
+        assert!(std::error::Error::source(&err).is_some());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn io_error_formats_as_path_colon_message_with_a_source() {
+        let path = PathBuf::from("/nonexistent/path/to/manifest.yaml");
+        let err = Manifest::from_path_no_inherit(path.as_path()).unwrap_err();
+        let rendered = err.to_string();
+        assert!(rendered.starts_with(&format!("{}: ", path.display())));
+        assert!(std::error::Error::source(&err).is_some());
     }
 }
\ No newline at end of file